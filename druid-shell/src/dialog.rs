@@ -15,30 +15,59 @@
 //! File open/save dialogs.
 
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 /// Information about a file to be opened or saved.
 #[derive(Debug, Clone)]
 pub struct FileInfo {
     pub(crate) path: PathBuf,
+    pub(crate) format: Option<FileSpec>,
 }
 
 /// Type of file dialog.
 pub enum FileDialogType {
     /// File open dialog.
+    ///
+    /// When [`FileDialogOptions::multi_selection`] is set, the user may
+    /// confirm more than one path, and the caller should expect a
+    /// `Vec<FileInfo>` rather than a single [`FileInfo`] back from the dialog.
+    /// When [`FileDialogOptions::select_directories`] is also set, the
+    /// returned paths refer to directories instead of files.
+    ///
+    /// [`FileDialogOptions::multi_selection`]: struct.FileDialogOptions.html#method.multi_selection
+    /// [`FileDialogOptions::select_directories`]: struct.FileDialogOptions.html#method.select_directories
     Open,
     /// File save dialog.
     Save,
 }
 
 /// Options for file dialogs.
-#[derive(Debug, Clone, Default)]
+#[derive(Clone, Default)]
 pub struct FileDialogOptions {
     pub show_hidden: bool,
     pub allowed_types: Option<Vec<FileSpec>>,
+    pub(crate) multi_selection: bool,
+    pub(crate) select_directories: bool,
+    pub(crate) default_name: Option<String>,
+    pub(crate) files_filter: Option<Arc<dyn Fn(&Path) -> bool>>,
     // we don't want a library user to be able to construct this type directly
     __non_exhaustive: (),
-    // multi selection
-    // select directories
+}
+
+impl std::fmt::Debug for FileDialogOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FileDialogOptions")
+            .field("show_hidden", &self.show_hidden)
+            .field("allowed_types", &self.allowed_types)
+            .field("multi_selection", &self.multi_selection)
+            .field("select_directories", &self.select_directories)
+            .field("default_name", &self.default_name)
+            .field(
+                "files_filter",
+                &self.files_filter.as_ref().map(|_| "Fn(&Path) -> bool"),
+            )
+            .finish()
+    }
 }
 
 /// A description of a filetype, for specifiying allowed types in a file dialog.
@@ -49,7 +78,7 @@ pub struct FileDialogOptions {
 /// struct.
 ///
 /// [`COMDLG_FILTERSPEC`]: https://docs.microsoft.com/en-ca/windows/win32/api/shtypes/ns-shtypes-comdlg_filterspec
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct FileSpec {
     /// A human readable name, describing this filetype.
     ///
@@ -71,6 +100,20 @@ impl FileInfo {
     pub fn path(&self) -> &Path {
         &self.path
     }
+
+    /// The [`FileSpec`] that was selected in the dialog's type dropdown when
+    /// the user confirmed this file, if the dialog was given
+    /// [`FileSpec`]s in [`FileDialogOptions::allowed_types`] and the platform
+    /// reports this information.
+    ///
+    /// This is useful for a "Save As" flow that needs to pick a
+    /// serialization format based on the user's selected filter, which is
+    /// unambiguous in a way that the file's extension alone may not be.
+    ///
+    /// [`FileDialogOptions::allowed_types`]: struct.FileDialogOptions.html#structfield.allowed_types
+    pub fn format(&self) -> Option<&FileSpec> {
+        self.format.as_ref()
+    }
 }
 
 impl FileDialogOptions {
@@ -90,6 +133,54 @@ impl FileDialogOptions {
         self.allowed_types = Some(types);
         self
     }
+
+    /// Allow the user to select multiple files.
+    ///
+    /// Only relevant for the [`FileDialogType::Open`] dialog; has no effect
+    /// when saving a file.
+    ///
+    /// [`FileDialogType::Open`]: enum.FileDialogType.html#variant.Open
+    pub fn multi_selection(mut self) -> Self {
+        self.multi_selection = true;
+        self
+    }
+
+    /// Have the user select directories instead of files.
+    ///
+    /// Only relevant for the [`FileDialogType::Open`] dialog; has no effect
+    /// when saving a file.
+    ///
+    /// [`FileDialogType::Open`]: enum.FileDialogType.html#variant.Open
+    pub fn select_directories(mut self) -> Self {
+        self.select_directories = true;
+        self
+    }
+
+    /// Set a default filename that is pre-filled in the dialog, for use with
+    /// [`FileDialogType::Save`].
+    ///
+    /// [`FileDialogType::Save`]: enum.FileDialogType.html#variant.Save
+    pub fn default_name(mut self, default_name: impl Into<String>) -> Self {
+        self.default_name = Some(default_name.into());
+        self
+    }
+
+    /// Set an arbitrary predicate to filter the files shown in the dialog.
+    ///
+    /// This is more flexible than [`allowed_types`], which can only filter
+    /// on file extension; `filter` is given the full path of each candidate
+    /// and may inspect anything about it (file size, a sibling file's
+    /// presence, a name pattern, and so on).
+    ///
+    /// Backends that cannot apply a custom predicate natively will instead
+    /// post-filter an open dialog's result against `filter` before returning
+    /// it. The path returned by a save dialog is not filtered.
+    ///
+    /// [`allowed_types`]: #method.allowed_types
+    pub fn files_filter(mut self, filter: impl Fn(&Path) -> bool + 'static) -> Self {
+        self.files_filter = Some(Arc::new(filter));
+        self
+    }
 }
 
 impl FileSpec {
@@ -98,9 +189,212 @@ impl FileSpec {
     pub const GIF: FileSpec = FileSpec::new("Gif", &["gif"]);
     pub const PDF: FileSpec = FileSpec::new("PDF", &["pdf"]);
     pub const HTML: FileSpec = FileSpec::new("Web Page", &["htm", "html"]);
+    pub const IMAGES: FileSpec = FileSpec::new(
+        "Image",
+        &[
+            "png", "jpg", "jpeg", "gif", "bmp", "tiff", "webp", "svg", "ico",
+        ],
+    );
+    pub const VIDEOS: FileSpec = FileSpec::new(
+        "Video",
+        &["mp4", "mkv", "mov", "avi", "webm", "mpeg", "mpg", "wmv"],
+    );
+    pub const AUDIO: FileSpec =
+        FileSpec::new("Audio", &["mp3", "flac", "wav", "ogg", "aac", "m4a", "wma"]);
+    pub const ARCHIVES: FileSpec =
+        FileSpec::new("Archive", &["zip", "tar", "gz", "bz2", "7z", "rar", "xz"]);
 
     /// Create a new `FileSpec`.
     pub const fn new(name: &'static str, extensions: &'static [&'static str]) -> Self {
         FileSpec { name, extensions }
     }
+
+    /// Returns `true` if this spec matches "all files", and so should not
+    /// have an extension appended to save dialog filenames.
+    fn is_wildcard(&self) -> bool {
+        self.extensions.iter().any(|ext| *ext == "*")
+    }
+
+    /// Merge several `FileSpec`s into a single entry covering the union of
+    /// their extensions, so a dialog can present a combined "All supported"
+    /// filter alongside the individual ones, instead of every call site
+    /// having to build that list by hand.
+    ///
+    /// The extensions list this returns, like every other `FileSpec`'s, is
+    /// `&'static`; the first call for a given `(name, specs)` pair leaks a
+    /// slice to satisfy that, but the result is memoized, so calling this
+    /// repeatedly with the same arguments (e.g. from a widget's
+    /// `build`/`update`) reuses the cached `FileSpec` instead of leaking
+    /// again.
+    pub fn merge(name: &'static str, specs: &[FileSpec]) -> FileSpec {
+        static CACHE: Mutex<Vec<(&'static str, Vec<FileSpec>, FileSpec)>> = Mutex::new(Vec::new());
+
+        let mut cache = CACHE.lock().unwrap();
+        if let Some((.., merged)) = cache
+            .iter()
+            .find(|(cached_name, cached_specs, _)| *cached_name == name && cached_specs == specs)
+        {
+            return *merged;
+        }
+
+        let extensions: Vec<&'static str> = specs
+            .iter()
+            .flat_map(|spec| spec.extensions.iter().copied())
+            .collect();
+        let merged = FileSpec::new(name, Box::leak(extensions.into_boxed_slice()));
+        cache.push((name, specs.to_vec(), merged));
+        merged
+    }
+}
+
+/// Build the [`FileInfo`]s a backend should return from a finished open or
+/// save dialog.
+///
+/// `paths` are the raw selections the platform handed back (after whatever
+/// native filtering it applies); if `apply_files_filter` is set, this also
+/// applies [`FileDialogOptions::files_filter`] on top, for backends that
+/// can't do that filtering themselves. Every resulting [`FileInfo`] is
+/// stamped with `format`, the [`FileSpec`] that was active in the dialog's
+/// type dropdown at confirmation time.
+///
+/// Callers building the result of a [`FileDialogType::Save`] dialog should
+/// pass `false` for `apply_files_filter`: `files_filter` is meant to narrow
+/// down which of the *existing* entries an open dialog lists, and applying
+/// it to the single destination path a save dialog just produced would
+/// reject a perfectly good save target (and be indistinguishable from the
+/// user cancelling).
+///
+/// A backend that only supports a single selection should call this and
+/// take the first result; [`FileDialogOptions::multi_selection`] is what
+/// tells a backend to collect more than one path in the first place, and
+/// [`FileDialogOptions::select_directories`] is what tells it to collect
+/// directories rather than files.
+///
+/// [`FileDialogOptions::files_filter`]: struct.FileDialogOptions.html#method.files_filter
+/// [`FileDialogOptions::multi_selection`]: struct.FileDialogOptions.html#method.multi_selection
+/// [`FileDialogOptions::select_directories`]: struct.FileDialogOptions.html#method.select_directories
+/// [`FileDialogType::Save`]: enum.FileDialogType.html#variant.Save
+pub(crate) fn build_file_infos(
+    paths: Vec<PathBuf>,
+    format: Option<FileSpec>,
+    options: &FileDialogOptions,
+    apply_files_filter: bool,
+) -> Vec<FileInfo> {
+    paths
+        .into_iter()
+        .filter(|path| {
+            !apply_files_filter || options.files_filter.as_ref().map_or(true, |f| f(path))
+        })
+        .map(|path| FileInfo { path, format })
+        .collect()
+}
+
+/// Given a filename typed into a save dialog and the currently selected
+/// filter, return the filename with an appropriate extension appended, if
+/// one is needed.
+///
+/// If the text after `filename`'s final '.' is one of `filter`'s extensions
+/// (matched case-insensitively) this returns `filename` unchanged; otherwise
+/// it appends '.' and `filter`'s first extension. If `filter` is
+/// `None`, is a wildcard/"all files" spec, or has no extensions at all, no
+/// extension is appended.
+pub(crate) fn ensure_extension(filename: &str, filter: Option<&FileSpec>) -> String {
+    let filter = match filter {
+        Some(filter) if !filter.is_wildcard() && !filter.extensions.is_empty() => filter,
+        _ => return filename.to_string(),
+    };
+
+    let has_matching_extension = match filename.rfind('.') {
+        Some(dot_idx) => {
+            let existing_ext = &filename[dot_idx + 1..];
+            filter
+                .extensions
+                .iter()
+                .any(|ext| ext.eq_ignore_ascii_case(existing_ext))
+        }
+        None => false,
+    };
+
+    if has_matching_extension {
+        filename.to_string()
+    } else {
+        format!("{}.{}", filename, filter.extensions[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ensure_extension_appends_default_when_missing() {
+        assert_eq!(
+            ensure_extension("report", Some(&FileSpec::PDF)),
+            "report.pdf"
+        );
+    }
+
+    #[test]
+    fn ensure_extension_leaves_matching_extension_alone() {
+        assert_eq!(
+            ensure_extension("photo.JPEG", Some(&FileSpec::JPG)),
+            "photo.JPEG"
+        );
+    }
+
+    #[test]
+    fn ensure_extension_appends_after_trailing_dot() {
+        assert_eq!(
+            ensure_extension("notes.", Some(&FileSpec::TEXT)),
+            "notes..txt"
+        );
+    }
+
+    #[test]
+    fn ensure_extension_skips_wildcard_filter() {
+        let all_files = FileSpec::new("All Files", &["*"]);
+        assert_eq!(ensure_extension("data", Some(&all_files)), "data");
+    }
+
+    #[test]
+    fn ensure_extension_skips_filter_with_no_extensions() {
+        let empty = FileSpec::new("Empty", &[]);
+        assert_eq!(ensure_extension("data", Some(&empty)), "data");
+    }
+
+    #[test]
+    fn ensure_extension_skips_when_no_filter_selected() {
+        assert_eq!(ensure_extension("data", None), "data");
+    }
+
+    #[test]
+    fn merge_unions_extensions_in_order() {
+        let merged = FileSpec::merge("Documents", &[FileSpec::TEXT, FileSpec::PDF]);
+        assert_eq!(merged.name, "Documents");
+        assert_eq!(merged.extensions, &["txt", "pdf"]);
+    }
+
+    #[test]
+    fn merge_of_no_specs_has_no_extensions() {
+        let merged = FileSpec::merge("Nothing", &[]);
+        assert!(merged.extensions.is_empty());
+    }
+
+    #[test]
+    fn merge_is_memoized_for_identical_inputs() {
+        let first = FileSpec::merge("Media", &[FileSpec::JPG, FileSpec::GIF]);
+        let second = FileSpec::merge("Media", &[FileSpec::JPG, FileSpec::GIF]);
+        // Same extensions slice comes back rather than a freshly leaked one.
+        assert_eq!(
+            first.extensions.as_ptr(),
+            second.extensions.as_ptr(),
+            "repeated merge() calls with the same arguments should not leak again"
+        );
+    }
+
+    #[test]
+    fn is_wildcard_detects_star_extension() {
+        assert!(FileSpec::new("All Files", &["*"]).is_wildcard());
+        assert!(!FileSpec::TEXT.is_wildcard());
+    }
 }
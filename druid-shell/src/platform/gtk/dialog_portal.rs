@@ -0,0 +1,326 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! File dialogs driven by the `org.freedesktop.portal.FileChooser` D-Bus
+//! interface, for use inside Flatpak/Snap sandboxes where the GTK backend's
+//! usual direct `GtkFileChooserNative` path either fails outright or bypasses
+//! the portal's permission model.
+//!
+//! This backend is selected instead of [`dialog::gtk`] when [`use_portal`]
+//! reports that we're running sandboxed; otherwise the native GTK path is
+//! preferred, since it avoids the extra D-Bus round trip.
+//!
+//! [`dialog::gtk`]: ../dialog/index.html
+//! [`use_portal`]: fn.use_portal.html
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use url::Url;
+
+use crate::dialog::{build_file_infos, ensure_extension, FileDialogOptions, FileDialogType};
+use crate::dialog::{FileInfo, FileSpec};
+
+const PORTAL_DEST: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_IFACE: &str = "org.freedesktop.portal.FileChooser";
+const REQUEST_IFACE: &str = "org.freedesktop.portal.Request";
+/// How long to wait for the portal to answer a method call, or for the
+/// user to finish interacting with the dialog it opened, before giving up.
+const PORTAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Returns `true` if we're running inside a sandbox that should use the
+/// xdg-desktop-portal backend rather than talking to GTK directly.
+pub(crate) fn use_portal() -> bool {
+    Path::new("/.flatpak-info").exists() || std::env::var_os("SNAP").is_some()
+}
+
+/// Show an open or save dialog via the portal, returning the selected
+/// [`FileInfo`]s, or an empty `Vec` if the user cancelled.
+///
+/// [`FileInfo`]: ../../dialog/struct.FileInfo.html
+pub(crate) fn dialog(
+    ty: FileDialogType,
+    options: FileDialogOptions,
+) -> Result<Vec<FileInfo>, dbus::Error> {
+    let conn = Connection::new_session()?;
+    let proxy = conn.with_proxy(PORTAL_DEST, PORTAL_PATH, PORTAL_TIMEOUT);
+
+    let portal_options = build_portal_options(&ty, &options);
+    let method = match ty {
+        FileDialogType::Open => "OpenFile",
+        FileDialogType::Save => "SaveFile",
+    };
+
+    let (handle,): (dbus::Path,) =
+        proxy.method_call(PORTAL_IFACE, method, ("", "Select a file", portal_options))?;
+
+    let (response, results) = wait_for_response(&conn, &handle)?;
+    if response != 0 {
+        // 1 means the user cancelled, 2 means the interaction was otherwise
+        // ended; in both cases there's nothing selected to return.
+        return Ok(Vec::new());
+    }
+
+    let format = current_filter_format(&results, options.allowed_types.as_deref());
+    let mut paths: Vec<PathBuf> = prop_as_strings(&results, "uris")
+        .iter()
+        .filter_map(|uri| uri_to_path(uri))
+        .collect();
+
+    if let FileDialogType::Save = ty {
+        paths = paths
+            .into_iter()
+            .map(|path| normalize_save_path(path, format.as_ref()))
+            .collect();
+    }
+
+    let apply_files_filter = matches!(ty, FileDialogType::Open);
+    Ok(build_file_infos(
+        paths,
+        format,
+        &options,
+        apply_files_filter,
+    ))
+}
+
+/// Block until the `Response` signal for `handle` arrives on `conn`,
+/// returning the response code (`0` on success) and the portal's result
+/// dictionary.
+///
+/// Bounded by [`PORTAL_TIMEOUT`], the same timeout used for the method call
+/// that created `handle`: if the portal or the session bus connection dies
+/// before emitting `Response`, this returns an error instead of blocking the
+/// calling thread forever.
+///
+/// [`PORTAL_TIMEOUT`]: constant.PORTAL_TIMEOUT.html
+fn wait_for_response(
+    conn: &Connection,
+    handle: &dbus::Path,
+) -> Result<(u32, PropMap), dbus::Error> {
+    let (tx, rx) = mpsc::channel();
+    let rule = MatchRule::new_signal(REQUEST_IFACE, "Response").with_path(handle.clone());
+    let token = conn.add_match(rule, move |(response, results): (u32, PropMap), _, _| {
+        tx.send((response, results)).is_ok()
+    })?;
+
+    let deadline = std::time::Instant::now() + PORTAL_TIMEOUT;
+    let result = loop {
+        if std::time::Instant::now() >= deadline {
+            conn.remove_match(token)?;
+            return Err(dbus::Error::new_custom(
+                "org.druid.PortalTimeout",
+                "timed out waiting for the file chooser portal to respond",
+            ));
+        }
+        conn.process(Duration::from_millis(100))?;
+        if let Ok(result) = rx.try_recv() {
+            break result;
+        }
+    };
+
+    conn.remove_match(token)?;
+    Ok(result)
+}
+
+fn build_portal_options(ty: &FileDialogType, options: &FileDialogOptions) -> PropMap {
+    let mut portal_options = HashMap::new();
+
+    portal_options.insert(
+        "multiple".to_string(),
+        Variant(Box::new(options.multi_selection) as Box<dyn RefArg>),
+    );
+    portal_options.insert(
+        "directory".to_string(),
+        Variant(Box::new(options.select_directories) as Box<dyn RefArg>),
+    );
+
+    if let FileDialogType::Save = ty {
+        if let Some(name) = &options.default_name {
+            portal_options.insert(
+                "current_name".to_string(),
+                Variant(Box::new(name.clone()) as Box<dyn RefArg>),
+            );
+        }
+    }
+
+    if let Some(types) = &options.allowed_types {
+        let filters: Vec<(String, Vec<(u32, String)>)> = types
+            .iter()
+            .map(|spec| (spec.name.to_string(), file_spec_to_portal_filter(spec)))
+            .collect();
+        portal_options.insert(
+            "filters".to_string(),
+            Variant(Box::new(filters) as Box<dyn RefArg>),
+        );
+    }
+
+    portal_options
+}
+
+/// Convert a [`FileSpec`]'s extensions into the portal's filter representation:
+/// a list of `(filter_type, pattern)` pairs, where `0` means a glob pattern.
+///
+/// [`FileSpec`]: ../../dialog/struct.FileSpec.html
+fn file_spec_to_portal_filter(spec: &FileSpec) -> Vec<(u32, String)> {
+    spec.extensions
+        .iter()
+        .map(|ext| (0u32, format!("*.{}", ext)))
+        .collect()
+}
+
+/// Map the `current_filter` entry of a portal response back to the
+/// [`FileSpec`] it came from, by matching on the filter name we sent in
+/// `build_portal_options`.
+///
+/// [`FileSpec`]: ../../dialog/struct.FileSpec.html
+fn current_filter_format(
+    results: &PropMap,
+    allowed_types: Option<&[FileSpec]>,
+) -> Option<FileSpec> {
+    let allowed_types = allowed_types?;
+    let mut fields = results.get("current_filter")?.0.as_iter()?;
+    let name = fields.next()?.as_str()?;
+    allowed_types.iter().find(|spec| spec.name == name).copied()
+}
+
+/// Read a portal result entry that holds an array of strings, such as `uris`.
+fn prop_as_strings(results: &PropMap, key: &str) -> Vec<String> {
+    match results.get(key).and_then(|variant| variant.0.as_iter()) {
+        Some(iter) => iter
+            .filter_map(|item| item.as_str().map(str::to_string))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Parse a `file://` URI as returned by the portal into a `PathBuf`,
+/// percent-decoding it in the process.
+///
+/// The portal returns RFC 3986 URIs, so a selection like
+/// `/home/user/My File.txt` comes back as `file:///home/user/My%20File.txt`;
+/// a bare prefix-strip would leave the `%20` in place and produce a path
+/// that doesn't exist on disk.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+/// Apply [`ensure_extension`] to a save-dialog result's filename, using
+/// `format` (the filter active when the user confirmed) to pick the
+/// extension to append.
+///
+/// [`ensure_extension`]: ../../dialog/fn.ensure_extension.html
+fn normalize_save_path(path: PathBuf, format: Option<&FileSpec>) -> PathBuf {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(file_name) => path.with_file_name(ensure_extension(file_name, format)),
+        None => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(arg: impl RefArg + 'static) -> Variant<Box<dyn RefArg>> {
+        Variant(Box::new(arg))
+    }
+
+    #[test]
+    fn uri_to_path_decodes_percent_escapes() {
+        assert_eq!(
+            uri_to_path("file:///home/user/My%20File.txt"),
+            Some(PathBuf::from("/home/user/My File.txt"))
+        );
+    }
+
+    #[test]
+    fn uri_to_path_rejects_non_file_uris() {
+        assert_eq!(uri_to_path("http://example.com/a"), None);
+    }
+
+    #[test]
+    fn uri_to_path_rejects_garbage() {
+        assert_eq!(uri_to_path("not a uri"), None);
+    }
+
+    #[test]
+    fn prop_as_strings_reads_string_array() {
+        let mut results = PropMap::new();
+        results.insert(
+            "uris".to_string(),
+            variant(vec!["file:///a".to_string(), "file:///b".to_string()]),
+        );
+        assert_eq!(
+            prop_as_strings(&results, "uris"),
+            vec!["file:///a".to_string(), "file:///b".to_string()]
+        );
+    }
+
+    #[test]
+    fn prop_as_strings_missing_key_is_empty() {
+        let results = PropMap::new();
+        assert!(prop_as_strings(&results, "uris").is_empty());
+    }
+
+    #[test]
+    fn file_spec_to_portal_filter_builds_glob_patterns() {
+        assert_eq!(
+            file_spec_to_portal_filter(&FileSpec::JPG),
+            vec![(0u32, "*.jpg".to_string()), (0u32, "*.jpeg".to_string())]
+        );
+    }
+
+    #[test]
+    fn current_filter_format_matches_by_name() {
+        let mut results = PropMap::new();
+        results.insert(
+            "current_filter".to_string(),
+            variant((
+                "PDF".to_string(),
+                file_spec_to_portal_filter(&FileSpec::PDF),
+            )),
+        );
+        let allowed_types = [FileSpec::TEXT, FileSpec::PDF];
+
+        assert_eq!(
+            current_filter_format(&results, Some(&allowed_types)),
+            Some(FileSpec::PDF)
+        );
+    }
+
+    #[test]
+    fn current_filter_format_without_allowed_types_is_none() {
+        let mut results = PropMap::new();
+        results.insert(
+            "current_filter".to_string(),
+            variant((
+                "PDF".to_string(),
+                file_spec_to_portal_filter(&FileSpec::PDF),
+            )),
+        );
+        assert_eq!(current_filter_format(&results, None), None);
+    }
+
+    #[test]
+    fn current_filter_format_missing_key_is_none() {
+        let results = PropMap::new();
+        let allowed_types = [FileSpec::PDF];
+        assert_eq!(current_filter_format(&results, Some(&allowed_types)), None);
+    }
+}